@@ -0,0 +1,272 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2020 Joyent, Inc.
+ */
+
+/*
+ * A continuously-updating, in-place terminal dashboard, for watching long
+ * soak tests without scrolling through an endless log of '---' blocks.
+ *
+ * This redraws the same few lines in place on every tick (alternate screen +
+ * cursor repositioning, crossterm-style) instead of printing new output.
+ * It's meant to be watched, not parsed or redirected -- OutputFormat::Human
+ * and OutputFormat::Tabular remain the right choice for piping into a file
+ * or a plotting tool.
+ */
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::SystemTime;
+
+use crate::snapshot::{RingBuffer, TickSnapshot};
+use crate::utils::DataCap;
+use crate::worker::{Operation, WorkerStat};
+
+const HISTORY_TICKS: usize = 40;
+const ESC: &str = "\x1b";
+
+/*
+ * isatty(3) via a raw FFI binding rather than pulling in a terminal crate
+ * just for this one check -- if stdout isn't a tty (piped to a file, cron,
+ * etc.) the dashboard degrades to the plain Human format instead of filling
+ * the output with cursor-repositioning escape codes.
+ */
+extern "C" {
+    fn isatty(fd: i32) -> i32;
+}
+
+pub fn stdout_is_tty() -> bool {
+    unsafe { isatty(1) != 0 }
+}
+
+/* Show the cursor and switch back to the primary screen buffer. */
+fn restore_terminal() {
+    print!("{}[?25h{}[?1049l", ESC, ESC);
+    let _ = io::stdout().flush();
+}
+
+pub struct Dashboard {
+    history: RingBuffer<TickSnapshot>,
+    start_time: SystemTime,
+    entered_alt_screen: bool,
+}
+
+impl Dashboard {
+    pub fn new(start_time: SystemTime) -> Dashboard {
+        /*
+         * leave()/Drop only restore the terminal on a normal return from
+         * collect_stats (DataCap reached, statvfs error) -- the usual way
+         * an operator stops watching a long soak test is Ctrl-C, which
+         * exits mid-signal with no unwind and would otherwise strand the
+         * terminal in the alternate screen buffer with a hidden cursor.
+         * Install a SIGINT handler up front that restores it directly
+         * before exiting.
+         */
+        let _ = ctrlc::set_handler(|| {
+            restore_terminal();
+            std::process::exit(130);
+        });
+
+        Dashboard {
+            history: RingBuffer::new(HISTORY_TICKS),
+            start_time,
+            entered_alt_screen: false,
+        }
+    }
+
+    fn enter(&mut self) {
+        if self.entered_alt_screen {
+            return;
+        }
+        /* Switch to the alternate screen buffer and hide the cursor. */
+        print!("{}[?1049h{}[?25l", ESC, ESC);
+        self.entered_alt_screen = true;
+    }
+
+    /* Restore the primary screen buffer and cursor. Safe to call more than once. */
+    pub fn leave(&mut self) {
+        if !self.entered_alt_screen {
+            return;
+        }
+        restore_terminal();
+        self.entered_alt_screen = false;
+    }
+
+    fn sparkline(values: &[u64]) -> String {
+        const LEVELS: [char; 8] =
+            ['\u{2581}', '\u{2582}', '\u{2583}', '\u{2584}', '\u{2585}', '\u{2586}', '\u{2587}', '\u{2588}'];
+
+        let max = values.iter().copied().max().unwrap_or(0);
+        if max == 0 {
+            return LEVELS[0].to_string().repeat(values.len());
+        }
+
+        values
+            .iter()
+            .map(|&v| {
+                let idx = ((v as f64 / max as f64) * (LEVELS.len() - 1) as f64).round() as usize;
+                LEVELS[idx.min(LEVELS.len() - 1)]
+            })
+            .collect()
+    }
+
+    fn gauge_line(op: &Operation, worker: &WorkerStat, period_secs: f64) -> String {
+        if period_secs <= 0.0 || worker.objs == 0 {
+            return format!("{}: idle", op);
+        }
+
+        let objs_per_sec = worker.objs as f64 / period_secs;
+        let bytes_per_sec = worker.data as f64 / period_secs;
+        let mean_ttfb_ms = worker.ttfb_hist.p50() as f64 / 1000.0;
+
+        format!(
+            "{}: {:>8.1} obj/s  {:>10.1} KB/s  p50 ttfb {:.2}ms",
+            op,
+            objs_per_sec,
+            bytes_per_sec / 1024.0,
+            mean_ttfb_ms
+        )
+    }
+
+    /*
+     * Render one frame. Called on the same tick cadence as collect_stats.
+     * `op_ticks` is this tick's aggregate (per-operation), already computed
+     * by the caller; `op_agg` is the program-lifetime aggregate, used for the
+     * total error count in the footer when no DataCap is set. The DataCap
+     * progress footer uses `total_bytes_written` for LogicalData and
+     * `disk_pct_used` for Percentage. `disk_pct_used` is the live statvfs reading
+     * for DataCap::Percentage (fs backend only); None if that cap isn't
+     * active, the backend isn't fs, or the statvfs call failed this tick.
+     */
+    pub fn render(
+        &mut self,
+        op_ticks: &HashMap<Operation, WorkerStat>,
+        op_agg: &HashMap<Operation, WorkerStat>,
+        period_secs: f64,
+        total_bytes_written: u64,
+        data_cap: &Option<DataCap>,
+        disk_pct_used: Option<f64>,
+    ) {
+        self.enter();
+
+        let snap = TickSnapshot {
+            tick: self.history.len() as u64,
+            secs_since_start: self.start_time.elapsed().unwrap().as_secs(),
+            stats: op_ticks.clone(),
+        };
+        self.history.push(snap);
+
+        /* Move cursor to the top-left and clear the screen before redrawing. */
+        print!("{}[H{}[2J", ESC, ESC);
+
+        println!("chum -- live dashboard");
+        println!(
+            "elapsed: {}s",
+            self.start_time.elapsed().unwrap().as_secs()
+        );
+        println!();
+
+        for op in &[Operation::Read, Operation::Write] {
+            let zero = WorkerStat::new();
+            let worker = op_ticks.get(op).unwrap_or(&zero);
+            println!("{}", Self::gauge_line(op, worker, period_secs));
+
+            let history: Vec<u64> = self
+                .history
+                .window()
+                .iter()
+                .map(|s| s.stats.get(op).map(|w| w.objs).unwrap_or(0))
+                .collect();
+            println!("  {}", Self::sparkline(&history));
+        }
+
+        println!();
+        match data_cap {
+            Some(DataCap::LogicalData(cap)) => {
+                let pct = (total_bytes_written as f64 / *cap as f64) * 100.0;
+                println!(
+                    "progress: {}/{} bytes ({:.1}%)",
+                    total_bytes_written, cap, pct
+                );
+            }
+            Some(DataCap::Percentage(cap)) => match disk_pct_used {
+                Some(pct) => println!(
+                    "progress: {:.1}% disk used (target {}%)",
+                    pct, cap
+                ),
+                None => println!("progress: target {}% disk used", cap),
+            },
+            None => {
+                let errors = op_agg
+                    .get(&Operation::Error)
+                    .map(|w| w.objs)
+                    .unwrap_or(0);
+                println!("total errors: {}", errors);
+            }
+        }
+
+        let _ = io::stdout().flush();
+    }
+}
+
+impl Drop for Dashboard {
+    fn drop(&mut self) {
+        self.leave();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sparkline_all_zero_is_lowest_level() {
+        let line = Dashboard::sparkline(&[0, 0, 0]);
+        assert_eq!(line, "\u{2581}\u{2581}\u{2581}");
+    }
+
+    #[test]
+    fn test_sparkline_single_spike_hits_top_level() {
+        let line = Dashboard::sparkline(&[0, 100, 0]);
+        let levels: Vec<char> = line.chars().collect();
+        assert_eq!(levels[1], '\u{2588}');
+        assert_eq!(levels[0], levels[2]);
+    }
+
+    #[test]
+    fn test_sparkline_flat_ramp_is_monotonic() {
+        let line = Dashboard::sparkline(&[0, 25, 50, 75, 100]);
+        let levels: Vec<usize> = line
+            .chars()
+            .map(|c| c as usize)
+            .collect();
+        assert!(levels.windows(2).all(|w| w[0] <= w[1]));
+    }
+
+    #[test]
+    fn test_sparkline_length_matches_input() {
+        let values: Vec<u64> = (0..HISTORY_TICKS as u64).collect();
+        assert_eq!(Dashboard::sparkline(&values).chars().count(), values.len());
+    }
+
+    #[test]
+    fn test_gauge_line_idle_when_no_objects() {
+        let worker = WorkerStat::new();
+        let line = Dashboard::gauge_line(&Operation::Read, &worker, 1.0);
+        assert_eq!(line, "read: idle");
+    }
+
+    #[test]
+    fn test_gauge_line_reports_rates_when_active() {
+        let mut worker = WorkerStat::new();
+        worker.record(1024, 5_000, 10_000);
+        let line = Dashboard::gauge_line(&Operation::Write, &worker, 2.0);
+        assert!(line.starts_with("write:"));
+        assert!(line.contains("obj/s"));
+        assert!(line.contains("KB/s"));
+        assert!(line.contains("p50 ttfb"));
+    }
+}
@@ -0,0 +1,236 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2020 Joyent, Inc.
+ */
+
+/*
+ * A streaming, fixed-memory latency histogram. WorkerStat only ever carried
+ * summed ttfb/rtt, which is enough to report a mean but can't say anything
+ * about tail latency -- the number that actually matters for a load tester.
+ *
+ * This buckets samples logarithmically (base-2, with a few linear
+ * sub-buckets per power of two) so the histogram covers ~1us to ~60s with a
+ * small, constant number of buckets regardless of how many requests are
+ * recorded. Percentiles are then computed by walking the cumulative counts
+ * and interpolating within the bucket that contains the target rank.
+ */
+
+/* Buckets cover 2^0us .. 2^26us (~67s), with 4 linear sub-buckets per octave. */
+const MIN_POW: u32 = 0;
+const MAX_POW: u32 = 26;
+const SUBBUCKETS: u64 = 4;
+const NUM_BUCKETS: usize = ((MAX_POW - MIN_POW + 1) * SUBBUCKETS as u32) as usize;
+
+#[derive(Clone)]
+pub struct Histogram {
+    buckets: Vec<u64>,
+    count: u64,
+    max: u64,
+}
+
+impl Histogram {
+    pub fn new() -> Histogram {
+        Histogram {
+            buckets: vec![0; NUM_BUCKETS],
+            count: 0,
+            max: 0,
+        }
+    }
+
+    /*
+     * Maps a latency in microseconds to a bucket index. Each octave
+     * [2^p, 2^(p+1)) is split into SUBBUCKETS linear sub-buckets, so
+     * resolution stays proportional to the magnitude of the sample.
+     */
+    fn bucket_for(&self, micros: u64) -> usize {
+        if micros < 1 {
+            return 0;
+        }
+
+        let pow = 63 - micros.leading_zeros();
+        let pow = pow.min(MAX_POW);
+        let bucket_start = 1u64 << pow;
+        let bucket_width = bucket_start / SUBBUCKETS;
+        let sub = (micros - bucket_start)
+            .checked_div(bucket_width)
+            .unwrap_or(0)
+            .min(SUBBUCKETS - 1);
+
+        ((pow - MIN_POW) as u64 * SUBBUCKETS + sub) as usize
+    }
+
+    /* The microsecond value at the *start* of the given bucket. */
+    fn bucket_floor(&self, idx: usize) -> u64 {
+        let pow = MIN_POW + (idx as u32 / SUBBUCKETS as u32);
+        let sub = idx as u64 % SUBBUCKETS;
+        let bucket_start = 1u64 << pow;
+        let bucket_width = bucket_start.max(1) / SUBBUCKETS.max(1);
+        bucket_start + sub * bucket_width
+    }
+
+    fn bucket_width(&self, idx: usize) -> u64 {
+        let pow = MIN_POW + (idx as u32 / SUBBUCKETS as u32);
+        (1u64 << pow).max(1) / SUBBUCKETS.max(1)
+    }
+
+    pub fn record(&mut self, micros: u64) {
+        let idx = self.bucket_for(micros);
+        self.buckets[idx] += 1;
+        self.count += 1;
+        self.max = self.max.max(micros);
+    }
+
+    pub fn clear(&mut self) {
+        for b in self.buckets.iter_mut() {
+            *b = 0;
+        }
+        self.count = 0;
+        self.max = 0;
+    }
+
+    /*
+     * Merging is just element-wise addition of the bucket counts, which
+     * stays associative and commutative -- histograms from multiple worker
+     * threads can be summed in any order without losing precision.
+     */
+    pub fn merge(&mut self, other: &Histogram) {
+        for (a, b) in self.buckets.iter_mut().zip(other.buckets.iter()) {
+            *a += b;
+        }
+        self.count += other.count;
+        self.max = self.max.max(other.max);
+    }
+
+    /* Interpolated microsecond value at the given percentile (0.0 - 100.0). */
+    pub fn percentile(&self, p: f64) -> u64 {
+        if self.count == 0 {
+            return 0;
+        }
+
+        let target = ((p / 100.0) * self.count as f64).ceil() as u64;
+        let target = target.max(1);
+
+        let mut cumulative: u64 = 0;
+        for (idx, &n) in self.buckets.iter().enumerate() {
+            if n == 0 {
+                continue;
+            }
+
+            if cumulative + n >= target {
+                let rank_in_bucket = target - cumulative;
+                let frac = rank_in_bucket as f64 / n as f64;
+                let floor = self.bucket_floor(idx);
+                let width = self.bucket_width(idx);
+                /*
+                 * frac is inclusive of the sample that lands the target rank,
+                 * so scaling by the full bucket width can walk past every
+                 * sample actually recorded in the bucket. Clamp to what was
+                 * observed instead of reporting a latency nothing hit.
+                 */
+                return (floor + (frac * width as f64) as u64).min(self.max);
+            }
+
+            cumulative += n;
+        }
+
+        self.bucket_floor(NUM_BUCKETS - 1).min(self.max)
+    }
+
+    pub fn p50(&self) -> u64 {
+        self.percentile(50.0)
+    }
+
+    pub fn p90(&self) -> u64 {
+        self.percentile(90.0)
+    }
+
+    pub fn p99(&self) -> u64 {
+        self.percentile(99.0)
+    }
+
+    pub fn p999(&self) -> u64 {
+        self.percentile(99.9)
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Histogram::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_percentiles_are_zero() {
+        let h = Histogram::new();
+        assert_eq!(h.p50(), 0);
+        assert_eq!(h.p99(), 0);
+    }
+
+    #[test]
+    fn test_uniform_samples() {
+        let mut h = Histogram::new();
+        for v in 1..=1000u64 {
+            h.record(v);
+        }
+
+        /*
+         * Bucketing is logarithmic, so percentiles are only approximate --
+         * just check they land within the right order of magnitude.
+         */
+        let p50 = h.p50();
+        assert!(p50 > 400 && p50 < 600, "p50 was {}", p50);
+
+        let p99 = h.p99();
+        assert!(p99 > 900 && p99 <= 1000, "p99 was {}", p99);
+    }
+
+    #[test]
+    fn test_merge_matches_recording_all_samples() {
+        let mut a = Histogram::new();
+        let mut b = Histogram::new();
+        for v in 1..=100u64 {
+            a.record(v);
+        }
+        for v in 101..=200u64 {
+            b.record(v);
+        }
+
+        let mut merged = a.clone();
+        merged.merge(&b);
+
+        let mut total = Histogram::new();
+        for v in 1..=200u64 {
+            total.record(v);
+        }
+
+        assert_eq!(merged.count, total.count);
+        assert_eq!(merged.buckets, total.buckets);
+    }
+
+    #[test]
+    fn test_percentile_never_exceeds_recorded_max() {
+        let mut h = Histogram::new();
+        for v in 1..=1000u64 {
+            h.record(v);
+        }
+
+        assert!(h.percentile(100.0) <= 1000);
+        assert_eq!(h.percentile(100.0), 1000);
+    }
+
+    #[test]
+    fn test_clear_resets_counts() {
+        let mut h = Histogram::new();
+        h.record(500);
+        h.clear();
+        assert_eq!(h.count, 0);
+        assert_eq!(h.p50(), 0);
+    }
+}
@@ -22,6 +22,13 @@ pub enum QueueMode {
     Lru,
     Mru,
     Rand,
+    /*
+     * Zipf models a hot-key workload: a small fraction of items receive
+     * most of the traffic rather than every item being equally likely.
+     * `theta` is the skew parameter (0 is uniform, close to 1 is very
+     * skewed -- 0.99 is the classic YCSB default).
+     */
+    Zipf { theta: f64 },
 }
 
 #[derive(Debug)]
@@ -39,11 +46,31 @@ impl fmt::Display for QueueModeError {
 
 /*
  * To make calling code cleaner, let users create the QueueMode from a
- * lowercase str.
+ * lowercase str. Zipf additionally takes a ':'-separated theta, e.g.
+ * 'zipf:0.99'.
  */
 impl FromStr for QueueMode {
     type Err = QueueModeError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(thetastr) = s.strip_prefix("zipf:") {
+            let theta: f64 = thetastr.parse().map_err(|_| QueueModeError)?;
+
+            /*
+             * The Gray-Zipf formula in zipf_index() is only valid for
+             * theta in (0, 1): at 1.0, alpha = 1.0/(1.0-theta) blows up
+             * to infinity, and at or below 0.0, eta's denominator can
+             * land on exactly zero for some n. Both cases degrade
+             * silently to NaN/garbage rather than panicking, so reject
+             * them here instead of letting the distribution quietly stop
+             * working.
+             */
+            if theta <= 0.0 || theta >= 1.0 {
+                return Err(QueueModeError);
+            }
+
+            return Ok(QueueMode::Zipf { theta });
+        }
+
         let mode = match s {
             "lru" => Some(QueueMode::Lru),
             "mru" => Some(QueueMode::Mru),
@@ -60,12 +87,12 @@ impl FromStr for QueueMode {
 
 impl fmt::Display for QueueMode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let strmode = match self {
-            QueueMode::Lru => "lru",
-            QueueMode::Mru => "mru",
-            QueueMode::Rand => "rand",
-        };
-        write!(f, "{}", strmode)
+        match self {
+            QueueMode::Lru => write!(f, "lru"),
+            QueueMode::Mru => write!(f, "mru"),
+            QueueMode::Rand => write!(f, "rand"),
+            QueueMode::Zipf { theta } => write!(f, "zipf:{}", theta),
+        }
     }
 }
 
@@ -74,6 +101,16 @@ pub struct Queue<T> {
     cap: usize,
     mode: QueueMode,
     cursor: usize,
+    /*
+     * Zipf-mode-only bookkeeping. `zipf_zeta` is the running
+     * zeta(n, theta) normalization constant, updated incrementally by one
+     * term each time the queue grows so that insert() never has to re-sum
+     * it from scratch. `last_write` tracks the index most recently written
+     * to, so that Zipf rank 0 (the "hottest" item) always resolves to the
+     * freshest write.
+     */
+    zipf_zeta: f64,
+    last_write: usize,
 }
 
 /*
@@ -88,6 +125,9 @@ pub struct Queue<T> {
  *   new item is added to the top of the stack.
  * - Rand (random). Operates like an array. Random items are returned when using
  *   the accessor function. New items replace a random item.
+ * - Zipf (weighted hot-key). Like Rand, but item indices are drawn from a
+ *   Zipf distribution instead of uniformly, so a small fraction of items
+ *   (the most recently written ones) account for most of the accesses.
  */
 impl<T> Queue<T> {
     pub fn new(mode: QueueMode) -> Queue<T> {
@@ -96,6 +136,8 @@ impl<T> Queue<T> {
             cap: DEF_QUEUE_CAP,
             mode,
             cursor: 0,
+            zipf_zeta: 0.0,
+            last_write: 0,
         }
     }
 
@@ -106,6 +148,12 @@ impl<T> Queue<T> {
     pub fn insert(&mut self, qi: T) {
         if self.items.len() < self.cap {
             self.items.push(qi);
+            self.last_write = self.items.len() - 1;
+
+            if let QueueMode::Zipf { theta } = self.mode {
+                let n = self.items.len() as f64;
+                self.zipf_zeta += 1.0 / n.powf(theta);
+            }
             return;
         }
 
@@ -127,6 +175,10 @@ impl<T> Queue<T> {
             QueueMode::Rand => self
                 .items
                 .get(rand::thread_rng().gen_range(0, self.items.len())),
+            QueueMode::Zipf { theta } => {
+                let idx = self.zipf_index(theta);
+                self.items.get(idx)
+            }
         }
     }
 
@@ -147,9 +199,61 @@ impl<T> Queue<T> {
                 }
                 ret
             }
+            QueueMode::Zipf { theta } => {
+                let idx = self.zipf_index(theta);
+                Some(self.zipf_remove_at(idx))
+            }
         }
     }
 
+    /*
+     * swap_remove()-based removal for Zipf mode, split out of remove() so
+     * the last_write bookkeeping below can be exercised with a known index
+     * in tests instead of depending on the random Zipf draw.
+     */
+    fn zipf_remove_at(&mut self, idx: usize) -> T {
+        let old_n = self.items.len();
+        let last_index = old_n - 1;
+        let removed_freshest = idx == self.last_write;
+
+        let ret = self.items.swap_remove(idx);
+        let new_len = self.items.len();
+
+        /*
+         * zeta(n, theta) is the sum of 1/k^theta for k in 1..=n. insert()
+         * grows it by one term as n grows; removing an item shrinks n by
+         * one, so undo exactly the term that growing back to old_n added.
+         * Without this, a steady-state remove-then-insert workload (n never
+         * actually changes) would still add a fresh term on every insert()
+         * and never remove one, so zeta drifts upward forever instead of
+         * tracking the zeta of the current n.
+         */
+        if let QueueMode::Zipf { theta } = self.mode {
+            self.zipf_zeta -= 1.0 / (old_n as f64).powf(theta);
+        }
+
+        if removed_freshest {
+            /*
+             * The freshest item is gone, and with it the only bookkeeping
+             * we keep for recency order. Fall back to the current tail --
+             * not perfectly fresh, but the best guess without tracking a
+             * full recency list.
+             */
+            self.last_write = new_len.saturating_sub(1);
+        } else if self.last_write == last_index && idx != last_index {
+            /*
+             * swap_remove() moves the tail element into the removed slot.
+             * If the tail held the freshest item, follow it to its new
+             * home instead of losing track of it.
+             */
+            self.last_write = idx;
+        } else if self.last_write >= new_len {
+            self.last_write = new_len.saturating_sub(1);
+        }
+
+        ret
+    }
+
     pub fn replace(&mut self, qi: T) {
         if self.items.is_empty() {
             return;
@@ -164,8 +268,46 @@ impl<T> Queue<T> {
                 self.items[self.cursor] = qi;
                 self.cursor = (self.cursor + 1) % len;
             }
+            QueueMode::Zipf { .. } => {
+                self.items[self.cursor] = qi;
+                self.last_write = self.cursor;
+                self.cursor = (self.cursor + 1) % len;
+            }
         }
     }
+
+    /*
+     * Draws an item index following a Zipf distribution over the current
+     * items, using the standard Gray-Zipf generator. Rank 0 is remapped to
+     * `last_write` (the freshest item) and higher ranks walk backwards from
+     * there, so "hot" always means "recently inserted."
+     *
+     * `zipf_zeta` is kept up to date incrementally by insert(), so the only
+     * work done here is deriving the (cheap, O(1)) alpha/eta constants for
+     * the current theta and n, then taking one draw.
+     */
+    fn zipf_index(&self, theta: f64) -> usize {
+        let n = self.items.len();
+        let zeta = self.zipf_zeta;
+        let zeta2 = 1.0 + 0.5_f64.powf(theta);
+        let alpha = 1.0 / (1.0 - theta);
+        let eta = (1.0 - (2.0 / n as f64).powf(1.0 - theta))
+            / (1.0 - zeta2 / zeta);
+
+        let u: f64 = rand::thread_rng().gen_range(0.0, 1.0);
+        let uz = u * zeta;
+
+        let rank = if uz < 1.0 {
+            0
+        } else if uz < 1.0 + 0.5_f64.powf(theta) {
+            1
+        } else {
+            (n as f64 * (eta * u - eta + 1.0).powf(alpha)).floor() as usize
+        };
+        let rank = rank.min(n - 1);
+
+        (self.last_write + n - rank) % n
+    }
 }
 
 #[cfg(test)]
@@ -223,4 +365,123 @@ mod tests {
         let end = start.elapsed().as_millis();
         println!("removing {} items took {}ms", DEF_QUEUE_CAP, end);
     }
+
+    #[test]
+    fn test_zipf_parse() {
+        let mode: QueueMode = "zipf:0.99".parse().unwrap();
+        match mode {
+            QueueMode::Zipf { theta } => assert!((theta - 0.99).abs() < f64::EPSILON),
+            _ => panic!("expected QueueMode::Zipf"),
+        }
+        assert_eq!(mode.to_string(), "zipf:0.99");
+
+        assert!("zipf:".parse::<QueueMode>().is_err());
+        assert!("zipf:abc".parse::<QueueMode>().is_err());
+    }
+
+    #[test]
+    fn test_zipf_rejects_theta_out_of_range() {
+        assert!("zipf:0".parse::<QueueMode>().is_err());
+        assert!("zipf:0.0".parse::<QueueMode>().is_err());
+        assert!("zipf:-0.5".parse::<QueueMode>().is_err());
+        assert!("zipf:1".parse::<QueueMode>().is_err());
+        assert!("zipf:1.0".parse::<QueueMode>().is_err());
+        assert!("zipf:1.5".parse::<QueueMode>().is_err());
+
+        assert!("zipf:0.01".parse::<QueueMode>().is_ok());
+        assert!("zipf:0.99".parse::<QueueMode>().is_ok());
+    }
+
+    #[test]
+    fn test_zipf_skews_toward_recent_writes() {
+        let mut q = Queue::new(QueueMode::Zipf { theta: 0.99 });
+        for i in 0..100 {
+            q.insert(i);
+        }
+
+        /*
+         * The most recently inserted item (99) should come back far more
+         * often than an arbitrary older one, since rank 0 is remapped to
+         * the freshest write.
+         */
+        let mut hot_hits = 0;
+        let mut cold_hits = 0;
+        for _ in 0..10_000 {
+            match q.get() {
+                Some(&99) => hot_hits += 1,
+                Some(&0) => cold_hits += 1,
+                _ => (),
+            }
+        }
+
+        assert!(
+            hot_hits > cold_hits * 10,
+            "expected the most recent item to dominate: hot={} cold={}",
+            hot_hits,
+            cold_hits
+        );
+    }
+
+    #[test]
+    fn test_zipf_remove_follows_relocated_tail() {
+        let mut q = Queue::new(QueueMode::Zipf { theta: 0.99 });
+        for i in 0..10 {
+            q.insert(i);
+        }
+        assert_eq!(q.last_write, 9);
+
+        /*
+         * swap_remove(2) moves the tail element (9, the freshest item)
+         * into slot 2. last_write must follow it there instead of
+         * resetting to a cold slot.
+         */
+        let removed = q.zipf_remove_at(2);
+        assert_eq!(removed, 2);
+        assert_eq!(q.items[2], 9);
+        assert_eq!(q.last_write, 2);
+    }
+
+    #[test]
+    fn test_zipf_zeta_stable_under_remove_insert_cycles() {
+        let theta = 0.99;
+        let mut q = Queue::new(QueueMode::Zipf { theta });
+        for i in 0..1000 {
+            q.insert(i);
+        }
+
+        let reference_zeta: f64 = (1..=1000).map(|k| 1.0 / (k as f64).powf(theta)).sum();
+        assert!((q.zipf_zeta - reference_zeta).abs() < 1e-9);
+
+        /*
+         * A steady-state read-queue workload: n never actually changes, but
+         * naively accumulating a fresh zeta term on every insert() (without
+         * undoing one on remove()) would have zipf_zeta drift upward
+         * without bound over a long run.
+         */
+        for i in 0..5000 {
+            q.remove();
+            q.insert(1000 + i);
+        }
+
+        assert!(
+            (q.zipf_zeta - reference_zeta).abs() < 1e-6,
+            "zipf_zeta drifted: got {}, expected {}",
+            q.zipf_zeta,
+            reference_zeta
+        );
+    }
+
+    #[test]
+    fn test_zipf_remove_of_freshest_falls_back_to_tail() {
+        let mut q = Queue::new(QueueMode::Zipf { theta: 0.99 });
+        for i in 0..10 {
+            q.insert(i);
+        }
+        assert_eq!(q.last_write, 9);
+
+        /* Removing the freshest item directly has no relocated item to follow. */
+        let removed = q.zipf_remove_at(9);
+        assert_eq!(removed, 9);
+        assert_eq!(q.last_write, 8);
+    }
 }
@@ -0,0 +1,515 @@
+/*
+ * This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/.
+ *
+ * Copyright 2020 Joyent, Inc.
+ */
+
+/*
+ * This module implements an "event-triggered" rolling snapshot buffer.
+ * collect_stats() normally just prints the current tick and throws the
+ * history away. That's fine for watching a run live, but it means that when
+ * something goes wrong (a burst of errors, a latency spike) there's nothing
+ * left to look at afterward.
+ *
+ * A RingBuffer<TickSnapshot> keeps the last N per-tick aggregates around. An
+ * EventDetector looks at each new tick (with the trailing history for
+ * context) and decides whether it represents something worth keeping. When
+ * it does, the ring is dumped to a clip file once the configured number of
+ * post-event ticks has been observed: the window of ticks leading up to the
+ * event plus those ticks after it.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::utils::{parse_interval, ChumError};
+use crate::worker::{Operation, WorkerStat};
+
+const DEF_ERROR_THRESHOLD: u64 = 50;
+const DEF_DEVIATION: f64 = 0.5;
+const DEF_TRAILING_WINDOW: usize = 10;
+const DEF_WINDOW: usize = 120;
+const DEF_POST_EVENT_TICKS: u32 = 10;
+const DEF_FAST_INTERVAL_MS: u64 = 100;
+const DEF_CLIP_DIR: &str = ".";
+
+/*
+ * A single tick's worth of aggregated stats, keyed by operation. This is
+ * what gets retained in the ring and serialized out to a clip file -- it's
+ * deliberately a plain snapshot (not a reference into op_ticks) so it
+ * outlives the tick that produced it.
+ */
+#[derive(Clone)]
+pub struct TickSnapshot {
+    pub tick: u64,
+    pub secs_since_start: u64,
+    pub stats: HashMap<Operation, WorkerStat>,
+}
+
+/*
+ * Fixed-capacity ring buffer. Once full, inserting a new element overwrites
+ * the oldest one. This is the same "overwrite the tail" approach Queue uses
+ * for its Lru mode, just specialized to avoid pulling in the queue's
+ * get/remove semantics, which don't apply here (we only ever want the whole
+ * window, in order).
+ */
+pub struct RingBuffer<T> {
+    buf: Vec<Option<T>>,
+    cap: usize,
+    next: usize,
+    len: usize,
+}
+
+impl<T: Clone> RingBuffer<T> {
+    pub fn new(cap: usize) -> RingBuffer<T> {
+        assert!(cap > 0, "ring buffer capacity must be non-zero");
+        RingBuffer {
+            buf: vec![None; cap],
+            cap,
+            next: 0,
+            len: 0,
+        }
+    }
+
+    pub fn push(&mut self, item: T) {
+        self.buf[self.next] = Some(item);
+        self.next = (self.next + 1) % self.cap;
+        if self.len < self.cap {
+            self.len += 1;
+        }
+    }
+
+    /*
+     * Returns the contents of the ring in chronological (oldest-first)
+     * order.
+     */
+    pub fn window(&self) -> Vec<T> {
+        let start = if self.len < self.cap {
+            0
+        } else {
+            self.next
+        };
+
+        (0..self.len)
+            .map(|i| self.buf[(start + i) % self.cap].clone().unwrap())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/*
+ * An EventDetector decides, given the newest tick and the trailing history
+ * that preceded it (oldest-first, not yet including `current`), whether this
+ * tick is "interesting" enough to flush a clip for. Implementing this as a
+ * trait lets us plug in different predicates (error bursts, latency
+ * deviation, both at once) without collect_stats needing to know which one
+ * is active.
+ */
+pub trait EventDetector {
+    fn is_event(&mut self, history: &[TickSnapshot], current: &TickSnapshot) -> bool;
+}
+
+/*
+ * Fires when the error count for a tick crosses an absolute threshold, or
+ * when read/write throughput or p99 ttfb deviates from the trailing average
+ * by at least `deviation` (e.g. 0.5 == 50%).
+ */
+pub struct ThresholdDetector {
+    pub error_threshold: u64,
+    pub deviation: f64,
+    pub trailing_window: usize,
+}
+
+impl ThresholdDetector {
+    fn trailing_avg(
+        &self,
+        history: &[TickSnapshot],
+        op: Operation,
+        metric: fn(&WorkerStat) -> f64,
+    ) -> Option<f64> {
+        let samples: Vec<&TickSnapshot> = history
+            .iter()
+            .rev()
+            .take(self.trailing_window)
+            .collect();
+
+        if samples.is_empty() {
+            return None;
+        }
+
+        let sum: f64 = samples
+            .iter()
+            .filter_map(|s| s.stats.get(&op))
+            .map(metric)
+            .sum();
+
+        Some(sum / samples.len() as f64)
+    }
+
+    fn metric_deviates(
+        &self,
+        history: &[TickSnapshot],
+        current: &TickSnapshot,
+        op: Operation,
+        metric: fn(&WorkerStat) -> f64,
+    ) -> bool {
+        let avg = match self.trailing_avg(history, op, metric) {
+            Some(avg) if avg > 0.0 => avg,
+            _ => return false,
+        };
+
+        let cur = current.stats.get(&op).map(metric).unwrap_or(0.0);
+
+        ((cur - avg).abs() / avg) >= self.deviation
+    }
+
+    /* Throughput (objects per tick) or p99 ttfb. */
+    fn deviates(&self, history: &[TickSnapshot], current: &TickSnapshot, op: Operation) -> bool {
+        self.metric_deviates(history, current, op, |w| w.objs as f64)
+            || self.metric_deviates(history, current, op, |w| w.ttfb_hist.p99() as f64)
+    }
+}
+
+impl EventDetector for ThresholdDetector {
+    fn is_event(&mut self, history: &[TickSnapshot], current: &TickSnapshot) -> bool {
+        let errors = current
+            .stats
+            .get(&Operation::Error)
+            .map(|w| w.objs)
+            .unwrap_or(0);
+
+        if errors >= self.error_threshold {
+            return true;
+        }
+
+        self.deviates(history, current, Operation::Read)
+            || self.deviates(history, current, Operation::Write)
+    }
+}
+
+/*
+ * CLI-facing config for the snapshot subsystem, parsed from a single flag
+ * value (e.g. '-S error:50,deviation:0.5,window:120,post:10,fast:100ms,
+ * dir:/var/chum/clips') the same way -m parses QueueMode and -o parses
+ * OutputFormat: a comma-separated list of key:value pairs, any of which can
+ * be omitted to fall back to its default.
+ */
+pub struct SnapshotCliConfig {
+    pub error_threshold: u64,
+    pub deviation: f64,
+    pub trailing_window: usize,
+    pub window: usize,
+    pub post_event_ticks: u32,
+    pub fast_interval: Duration,
+    pub clip_dir: String,
+}
+
+impl Default for SnapshotCliConfig {
+    fn default() -> Self {
+        SnapshotCliConfig {
+            error_threshold: DEF_ERROR_THRESHOLD,
+            deviation: DEF_DEVIATION,
+            trailing_window: DEF_TRAILING_WINDOW,
+            window: DEF_WINDOW,
+            post_event_ticks: DEF_POST_EVENT_TICKS,
+            fast_interval: Duration::from_millis(DEF_FAST_INTERVAL_MS),
+            clip_dir: DEF_CLIP_DIR.to_string(),
+        }
+    }
+}
+
+impl FromStr for SnapshotCliConfig {
+    type Err = ChumError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = SnapshotCliConfig::default();
+
+        if s.is_empty() {
+            return Ok(config);
+        }
+
+        for kv in s.split(',') {
+            let mut parts = kv.splitn(2, ':');
+            let key = parts.next().unwrap_or("");
+            let val = parts.next().ok_or_else(|| {
+                ChumError::new(&format!("'{}' is not a valid key:value pair", kv))
+            })?;
+
+            match key {
+                "error" => {
+                    config.error_threshold = val
+                        .parse()
+                        .map_err(|_| ChumError::new(&format!("'{}' is not a valid error threshold", val)))?
+                }
+                "deviation" => {
+                    config.deviation = val
+                        .parse()
+                        .map_err(|_| ChumError::new(&format!("'{}' is not a valid deviation", val)))?
+                }
+                "trailing" => {
+                    config.trailing_window = val
+                        .parse()
+                        .map_err(|_| ChumError::new(&format!("'{}' is not a valid trailing window", val)))?
+                }
+                "window" => {
+                    config.window = val
+                        .parse()
+                        .map_err(|_| ChumError::new(&format!("'{}' is not a valid window", val)))?
+                }
+                "post" => {
+                    config.post_event_ticks = val
+                        .parse()
+                        .map_err(|_| ChumError::new(&format!("'{}' is not a valid post-event tick count", val)))?
+                }
+                "fast" => config.fast_interval = parse_interval(val)?,
+                "dir" => config.clip_dir = val.to_string(),
+                _ => return Err(ChumError::new(&format!("unrecognized snapshot option '{}'", key))),
+            }
+        }
+
+        if config.window == 0 {
+            return Err(ChumError::new("window must be greater than zero"));
+        }
+
+        /*
+         * The ring only ever holds `window` ticks. If post_event_ticks were
+         * allowed to reach or exceed that, draining the post-event window
+         * would push the triggering tick (and all its leading context) back
+         * out of the ring before flush_clip() ever runs, so the clip would
+         * ship without the event it was supposed to capture.
+         */
+        if config.post_event_ticks as usize >= config.window {
+            return Err(ChumError::new(
+                "post-event tick count must be smaller than the window",
+            ));
+        }
+
+        Ok(config)
+    }
+}
+
+/*
+ * Ties the ring buffer and detector together. collect_stats feeds it one
+ * tick at a time; when an event fires it asks for a few follow-up ticks at
+ * higher resolution via fast_ticks_remaining so the caller can shorten its
+ * sampling interval, and once those have been observed it writes out a clip
+ * file covering the ring's current window (the ticks leading up to, including
+ * and following the event).
+ */
+pub struct SnapshotManager {
+    ring: RingBuffer<TickSnapshot>,
+    detector: Box<dyn EventDetector + Send>,
+    clip_dir: String,
+    post_event_ticks: u32,
+    fast_ticks_remaining: u32,
+    fast_interval: Duration,
+}
+
+impl SnapshotManager {
+    pub fn new(
+        window: usize,
+        post_event_ticks: u32,
+        fast_interval: Duration,
+        detector: Box<dyn EventDetector + Send>,
+        clip_dir: String,
+    ) -> SnapshotManager {
+        SnapshotManager {
+            ring: RingBuffer::new(window),
+            detector,
+            clip_dir,
+            post_event_ticks,
+            fast_ticks_remaining: 0,
+            fast_interval,
+        }
+    }
+
+    /*
+     * While we're within the high-resolution window around an event, sample
+     * faster than the user's normal `interval` so the clip file has enough
+     * resolution to actually show what happened.
+     */
+    pub fn sample_interval(&self, normal_interval: Duration) -> Duration {
+        if self.fast_ticks_remaining > 0 {
+            self.fast_interval.min(normal_interval)
+        } else {
+            normal_interval
+        }
+    }
+
+    /*
+     * Feed a new tick in. Returns true if the fast (high resolution)
+     * sampling cadence should be used for the next tick, i.e. either an
+     * event just fired or we're still within its post-event window.
+     *
+     * The clip itself isn't written until the post-event window has
+     * actually drained -- writing at the instant the event fires would
+     * only ever capture history up to and including the triggering tick,
+     * never the "after" ticks the request asks for. A second event
+     * arriving while one window is already draining doesn't restart the
+     * countdown, so a burst of events doesn't truncate the clip early.
+     */
+    pub fn observe(&mut self, snap: TickSnapshot) -> bool {
+        let history = self.ring.window();
+        let is_event = self.detector.is_event(&history, &snap);
+
+        self.ring.push(snap);
+
+        if is_event && self.fast_ticks_remaining == 0 {
+            self.fast_ticks_remaining = self.post_event_ticks;
+            if self.fast_ticks_remaining == 0 {
+                /* No post-event window requested; write what we have now. */
+                self.flush_clip();
+            }
+        } else if self.fast_ticks_remaining > 0 {
+            self.fast_ticks_remaining -= 1;
+            if self.fast_ticks_remaining == 0 {
+                self.flush_clip();
+            }
+        }
+
+        self.fast_ticks_remaining > 0 || is_event
+    }
+
+    fn flush_clip(&self) {
+        if let Err(e) = self.write_clip() {
+            println!("failed to write snapshot clip: {}", e);
+        }
+    }
+
+    /*
+     * Builds a SnapshotManager straight out of a parsed CLI config, so the
+     * flag handler can turn '-S' into a running SnapshotManager in one line.
+     */
+    pub fn build(config: SnapshotCliConfig) -> SnapshotManager {
+        SnapshotManager::new(
+            config.window,
+            config.post_event_ticks,
+            config.fast_interval,
+            Box::new(ThresholdDetector {
+                error_threshold: config.error_threshold,
+                deviation: config.deviation,
+                trailing_window: config.trailing_window,
+            }),
+            config.clip_dir,
+        )
+    }
+
+    fn write_clip(&self) -> Result<(), ChumError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| ChumError::new(&e.to_string()))?
+            .as_secs();
+
+        let path = format!("{}/clip-{}.txt", self.clip_dir, now);
+        let mut f = File::create(&path)
+            .map_err(|e| ChumError::new(&format!("failed to create {}: {}", path, e)))?;
+
+        for snap in self.ring.window() {
+            for (op, worker) in snap.stats.iter() {
+                writeln!(
+                    f,
+                    "{}\t{}\t{}\t{}\t{}\t{}",
+                    snap.tick, snap.secs_since_start, op, worker.objs, worker.ttfb, worker.rtt
+                )
+                .map_err(|e| ChumError::new(&e.to_string()))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli_config_defaults() {
+        let config: SnapshotCliConfig = "".parse().unwrap();
+        assert_eq!(config.error_threshold, DEF_ERROR_THRESHOLD);
+        assert_eq!(config.window, DEF_WINDOW);
+        assert_eq!(config.fast_interval, Duration::from_millis(DEF_FAST_INTERVAL_MS));
+    }
+
+    #[test]
+    fn test_cli_config_overrides_only_given_keys() {
+        let config: SnapshotCliConfig = "error:10,fast:250ms,dir:/tmp/clips".parse().unwrap();
+        assert_eq!(config.error_threshold, 10);
+        assert_eq!(config.fast_interval, Duration::from_millis(250));
+        assert_eq!(config.clip_dir, "/tmp/clips");
+        /* Anything not mentioned keeps its default. */
+        assert_eq!(config.window, DEF_WINDOW);
+        assert_eq!(config.deviation, DEF_DEVIATION);
+    }
+
+    #[test]
+    fn test_cli_config_rejects_unknown_key() {
+        assert!("bogus:1".parse::<SnapshotCliConfig>().is_err());
+    }
+
+    #[test]
+    fn test_cli_config_rejects_zero_window() {
+        assert!("window:0".parse::<SnapshotCliConfig>().is_err());
+    }
+
+    #[test]
+    fn test_cli_config_rejects_post_event_ticks_not_smaller_than_window() {
+        assert!("window:3,post:8".parse::<SnapshotCliConfig>().is_err());
+        assert!("window:3,post:3".parse::<SnapshotCliConfig>().is_err());
+        assert!("window:3,post:2".parse::<SnapshotCliConfig>().is_ok());
+    }
+
+    /*
+     * An event opens a post-event window of post_event_ticks fast ticks,
+     * which counts down by one on each following tick (the clip is flushed
+     * when it reaches zero). A further event while the window is draining
+     * doesn't restart the countdown.
+     */
+    #[test]
+    fn test_observe_counts_down_post_event_window() {
+        struct AlwaysEvent;
+        impl EventDetector for AlwaysEvent {
+            fn is_event(&mut self, _history: &[TickSnapshot], _current: &TickSnapshot) -> bool {
+                true
+            }
+        }
+
+        let mut mgr = SnapshotManager::new(
+            5,
+            2,
+            Duration::from_millis(10),
+            Box::new(AlwaysEvent),
+            std::env::temp_dir().to_string_lossy().to_string(),
+        );
+
+        let snap = |tick| TickSnapshot {
+            tick,
+            secs_since_start: tick,
+            stats: HashMap::new(),
+        };
+
+        /* Event fires; the fast window should still be open. */
+        assert!(mgr.observe(snap(0)));
+        assert_eq!(mgr.fast_ticks_remaining, 2);
+
+        /* Post-event ticks still draining. */
+        assert!(mgr.observe(snap(1)));
+        assert_eq!(mgr.fast_ticks_remaining, 1);
+
+        /* Window drains back to zero, though the detector still fires. */
+        assert!(mgr.observe(snap(2)));
+        assert_eq!(mgr.fast_ticks_remaining, 0);
+    }
+}
@@ -14,13 +14,47 @@ use std::collections::HashMap;
 use std::error::Error;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
-use std::sync::{mpsc::Receiver, Arc, Mutex};
+use std::sync::{Arc, Mutex};
 use std::vec::Vec;
 use std::{thread, thread::ThreadId};
-use std::{time, time::SystemTime, time::UNIX_EPOCH};
+use std::{time, time::Instant, time::SystemTime, time::UNIX_EPOCH};
 
+use crate::accounting::StatAccumulator;
+use crate::dashboard::{stdout_is_tty, Dashboard};
 use crate::queue::Queue;
-use crate::worker::{Operation, WorkerInfo, WorkerStat};
+use crate::snapshot::{SnapshotManager, TickSnapshot};
+use crate::worker::{Operation, WorkerStat};
+
+/*
+ * Latency percentiles, in microseconds, pulled out of a WorkerStat's ttfb/rtt
+ * histograms. Kept as a small helper struct so print_human and print_tabular
+ * don't each have to repeat the same four histogram walks.
+ */
+struct LatencyPercentiles {
+    ttfb_p50: u64,
+    ttfb_p90: u64,
+    ttfb_p99: u64,
+    ttfb_p999: u64,
+    rtt_p50: u64,
+    rtt_p90: u64,
+    rtt_p99: u64,
+    rtt_p999: u64,
+}
+
+impl LatencyPercentiles {
+    fn from_worker(worker: &WorkerStat) -> LatencyPercentiles {
+        LatencyPercentiles {
+            ttfb_p50: worker.ttfb_hist.p50(),
+            ttfb_p90: worker.ttfb_hist.p90(),
+            ttfb_p99: worker.ttfb_hist.p99(),
+            ttfb_p999: worker.ttfb_hist.p999(),
+            rtt_p50: worker.rtt_hist.p50(),
+            rtt_p90: worker.rtt_hist.p90(),
+            rtt_p99: worker.rtt_hist.p99(),
+            rtt_p999: worker.rtt_hist.p999(),
+        }
+    }
+}
 
 /*
  * In the future we should use multiple '-v' flags for this:
@@ -35,6 +69,7 @@ pub enum OutputFormat {
     Human, /* prose, for humans watching the console. */
     HumanVerbose,
     Tabular, /* tab-separated, for throwing into something like gnuplot. */
+    Dashboard, /* continuously-updating in-place terminal view. */
 }
 
 impl std::str::FromStr for OutputFormat {
@@ -45,6 +80,7 @@ impl std::str::FromStr for OutputFormat {
             "h" => Ok(OutputFormat::Human),
             "v" => Ok(OutputFormat::HumanVerbose),
             "t" => Ok(OutputFormat::Tabular),
+            "d" => Ok(OutputFormat::Dashboard),
             _ => Err(ChumError::new("invalid operation requested")),
         }
     }
@@ -56,8 +92,8 @@ pub enum DataCap {
 }
 
 /*
- * This thread reads results off of the channel. This tracks three sets of
- * stats:
+ * This thread reduces every worker's shard once per tick. This tracks
+ * three sets of stats:
  * - long term aggregate statistics
  * - per tick aggregate statistics
  * - per thread-tick statistics
@@ -76,71 +112,95 @@ pub enum DataCap {
  * All stats are separated by operation (e.g. read, write, etc.).
  */
 pub fn collect_stats(
-    rx: Receiver<Result<WorkerInfo, ChumError>>,
-    interval: u64,
+    accumulator: Arc<StatAccumulator>,
+    interval: time::Duration,
     format: OutputFormat,
     data_cap: Option<DataCap>,
     target: String,
     protocol: String,
+    mut snapshot: Option<SnapshotManager>,
 ) {
-    let mut total_bytes_written: u64 = 0;
-    let mut op_agg = HashMap::new();
-    let start_time = SystemTime::now();
-
     /*
-     * This is copied code, and generally an abstraction leak. We should really
-     * implement a synchronous way of doing accounting that is guaranteed not
-     * to impact performance. Ideally this would tie in to the backend
-     * implementation somehow. The filesystem and webdav modes may do accounting
-     * in different ways, so we should allow them to have their own logic.
+     * The dashboard redraws in place using cursor-repositioning escapes,
+     * which only makes sense on an actual terminal. If stdout has been
+     * redirected (a file, a pipe, cron), fall back to the plain Human
+     * format instead of filling the output with escape codes.
      */
+    let format = if format == OutputFormat::Dashboard && !stdout_is_tty() {
+        OutputFormat::Human
+    } else {
+        format
+    };
 
-    loop {
-        thread::sleep(time::Duration::from_secs(interval));
-
-        let mut op_ticks = HashMap::new();
-        let mut op_stats = HashMap::new();
+    let mut total_bytes_written: u64 = 0;
+    let mut op_agg: HashMap<Operation, WorkerStat> = HashMap::new();
+    let start_time = SystemTime::now();
+    let start_instant = Instant::now();
+    let mut tick: u64 = 0;
+    let mut next_deadline = start_instant + interval;
+    let mut last_tick_instant = start_instant;
+    let mut dashboard = if format == OutputFormat::Dashboard {
+        Some(Dashboard::new(start_time))
+    } else {
+        None
+    };
 
+    loop {
         /*
-         * Catch up with the results that worker threads sent while this
-         * thread was sleeping.
+         * Sleep until the next absolute deadline rather than for a fixed
+         * relative duration, so that the time spent doing accounting and
+         * printing below doesn't accumulate into drift over a long run. If
+         * we've already fallen behind the deadline (a slow tick, or the
+         * snapshot manager switching cadence), don't sleep at all -- just
+         * resync the deadline off of "now" and carry on.
          */
-        for res in rx.try_iter() {
-            let wr: WorkerInfo;
-            match res {
-                Ok(wi) => wr = wi,
-                Err(e) => {
-                    if format == OutputFormat::HumanVerbose {
-                        println!("{}", e.to_string());
-                    }
-                    wr = WorkerInfo {
-                        id: thread::current().id(),
-                        op: Operation::Error,
-                        size: 0,
-                        ttfb: 0,
-                        rtt: 0,
-                    }
-                }
-            }
+        let now = Instant::now();
+        if next_deadline > now {
+            thread::sleep(next_deadline - now);
+        }
 
-            if wr.op == Operation::Write {
-                total_bytes_written += wr.size;
-            }
+        let tick_instant = Instant::now();
+        let actual_period = tick_instant - last_tick_instant;
+        last_tick_instant = tick_instant;
 
-            op_stats.entry(wr.op).or_insert_with(HashMap::new);
+        /*
+         * Reduce every worker's shard into this tick's per-operation
+         * totals (and, for the '-v' flag, a per-thread breakdown). Workers
+         * have been updating their own shards directly this whole time --
+         * there's no channel to drain here, just a brief lock per shard.
+         */
+        let (op_stats, op_ticks) = accumulator.reduce_and_reset();
 
-            let thread_stats = op_stats.get_mut(&wr.op).unwrap();
-            thread_stats.entry(wr.id).or_insert_with(WorkerStat::new);
-            thread_stats.get_mut(&wr.id).unwrap().add_result(&wr);
+        if let Some(write_stat) = op_ticks.get(&Operation::Write) {
+            total_bytes_written += write_stat.data;
+        }
 
-            op_ticks.entry(wr.op).or_insert_with(WorkerStat::new);
-            let tick_totals = op_ticks.get_mut(&wr.op).unwrap();
-            tick_totals.add_result(&wr);
+        for (op, stat) in op_ticks.iter() {
+            op_agg.entry(*op).or_default().merge(stat);
+        }
 
-            op_agg.entry(wr.op).or_insert_with(WorkerStat::new);
-            let agg_totals = op_agg.get_mut(&wr.op).unwrap();
-            agg_totals.add_result(&wr);
+        if let Some(mgr) = &mut snapshot {
+            mgr.observe(TickSnapshot {
+                tick,
+                secs_since_start: start_time.elapsed().unwrap().as_secs(),
+                stats: op_ticks.clone(),
+            });
         }
+        tick += 1;
+
+        /*
+         * Derive the interval that governs the *next* sleep only now, after
+         * observe() has had a chance to react to this tick. Computing it
+         * up front at the top of the loop would apply a fast/slow-cadence
+         * switch a full tick late: the sleep that just happened was
+         * already governed by the deadline set at the end of the
+         * *previous* iteration, before this tick's observe() ran.
+         */
+        let next_interval = match &snapshot {
+            Some(mgr) => mgr.sample_interval(interval),
+            None => interval,
+        };
+        next_deadline = tick_instant + next_interval;
 
         match format {
             OutputFormat::Human | OutputFormat::HumanVerbose => print_human(
@@ -149,6 +209,7 @@ pub fn collect_stats(
                 op_stats,
                 op_ticks,
                 &mut op_agg,
+                actual_period,
             ),
             OutputFormat::Tabular => print_tabular(
                 start_time,
@@ -156,7 +217,35 @@ pub fn collect_stats(
                 op_stats,
                 op_ticks,
                 &mut op_agg,
+                actual_period,
             ),
+            OutputFormat::Dashboard => {
+                /*
+                 * Percentage cap mode is fs-backend only, and the live view
+                 * should never fail a tick just because the statvfs call
+                 * did -- fall back to no reading rather than propagating
+                 * the error, the authoritative cap check below still does
+                 * that.
+                 */
+                let disk_pct_used = match &data_cap {
+                    Some(DataCap::Percentage(_)) if protocol == "fs" => {
+                        fs3::statvfs(&target).ok().map(|stats| {
+                            let used = stats.total_space() - stats.available_space();
+                            (used as f64 * 100.0) / stats.total_space() as f64
+                        })
+                    }
+                    _ => None,
+                };
+
+                dashboard.as_mut().unwrap().render(
+                    &op_ticks,
+                    &op_agg,
+                    actual_period.as_secs_f64(),
+                    total_bytes_written,
+                    &data_cap,
+                    disk_pct_used,
+                )
+            }
         }
 
         match data_cap {
@@ -199,7 +288,10 @@ fn print_human(
     mut op_stats: HashMap<Operation, HashMap<ThreadId, WorkerStat>>,
     mut op_ticks: HashMap<Operation, WorkerStat>,
     op_agg: &mut HashMap<Operation, WorkerStat>,
+    actual_period: time::Duration,
 ) {
+    let period_secs = actual_period.as_secs_f64();
+
     /* Print out the stats we gathered. */
     println!("---");
     if *format == OutputFormat::HumanVerbose {
@@ -218,7 +310,7 @@ fn print_human(
                 if op == &Operation::Error {
                     println!("\t{}: {} errors", i, worker.objs);
                 } else {
-                    println!("\t{}: {}", i, worker.serialize_relative());
+                    println!("\t{}: {}", i, worker.serialize_relative(period_secs));
                 }
                 worker.clear();
                 i += 1;
@@ -236,8 +328,19 @@ fn print_human(
         if op == &Operation::Error {
             println!("\t{} errors", worker.objs);
         } else {
-            println!("\t{}", worker.serialize_relative());
+            println!("\t{}", worker.serialize_relative(period_secs));
+            let lat = LatencyPercentiles::from_worker(worker);
+            println!(
+                "\t\tttfb (us) p50 {} p90 {} p99 {} p99.9 {}",
+                lat.ttfb_p50, lat.ttfb_p90, lat.ttfb_p99, lat.ttfb_p999
+            );
+            println!(
+                "\t\trtt (us)  p50 {} p90 {} p99 {} p99.9 {}",
+                lat.rtt_p50, lat.rtt_p90, lat.rtt_p99, lat.rtt_p999
+            );
         }
+        worker.ttfb_hist.clear();
+        worker.rtt_hist.clear();
     }
 
     for (op, worker) in op_agg.iter_mut() {
@@ -251,6 +354,15 @@ fn print_human(
             println!("\t{} errors", worker.objs);
         } else {
             println!("\t{}", worker.serialize_absolute(elapsed_sec));
+            let lat = LatencyPercentiles::from_worker(worker);
+            println!(
+                "\t\tttfb (us) p50 {} p90 {} p99 {} p99.9 {}",
+                lat.ttfb_p50, lat.ttfb_p90, lat.ttfb_p99, lat.ttfb_p999
+            );
+            println!(
+                "\t\trtt (us)  p50 {} p90 {} p99 {} p99.9 {}",
+                lat.rtt_p50, lat.rtt_p90, lat.rtt_p99, lat.rtt_p999
+            );
         }
     }
 }
@@ -259,8 +371,9 @@ fn print_tabular(
     _: SystemTime,
     _: &OutputFormat,
     _: HashMap<Operation, HashMap<ThreadId, WorkerStat>>,
-    op_ticks: HashMap<Operation, WorkerStat>,
+    mut op_ticks: HashMap<Operation, WorkerStat>,
     op_agg: &mut HashMap<Operation, WorkerStat>,
+    actual_period: time::Duration,
 ) {
     let zero_stat = WorkerStat::new();
 
@@ -300,8 +413,20 @@ fn print_tabular(
         None => &zero_stat,
     };
 
+    /*
+     * The tick period is no longer guaranteed to be exactly `interval`
+     * seconds (ticks can run long, or get shortened by the snapshot
+     * subsystem), so we emit it alongside the raw per-tick counts. Anything
+     * consuming this output (e.g. a gnuplot script) should divide by this
+     * column to get a rate instead of assuming a fixed interval.
+     */
+    let read_lat = LatencyPercentiles::from_worker(reader_stats);
+    let write_lat = LatencyPercentiles::from_worker(writer_stats);
+
     println!(
-        "{} {} {} {} {} {} {} {} {} {} {} {}",
+        "{} {} {} {} {} {} {} {} {} {} {} {} {:.3} \
+         {} {} {} {} {} {} {} {} \
+         {} {} {} {} {} {} {} {}",
         time,
         reader_stats.objs,
         writer_stats.objs,
@@ -314,7 +439,33 @@ fn print_tabular(
         error_stats.objs,
         agg_read.data,
         agg_write.data,
+        actual_period.as_secs_f64(),
+        read_lat.ttfb_p50,
+        read_lat.ttfb_p90,
+        read_lat.ttfb_p99,
+        read_lat.ttfb_p999,
+        read_lat.rtt_p50,
+        read_lat.rtt_p90,
+        read_lat.rtt_p99,
+        read_lat.rtt_p999,
+        write_lat.ttfb_p50,
+        write_lat.ttfb_p90,
+        write_lat.ttfb_p99,
+        write_lat.ttfb_p999,
+        write_lat.rtt_p50,
+        write_lat.rtt_p90,
+        write_lat.rtt_p99,
+        write_lat.rtt_p999,
     );
+
+    if let Some(w) = op_ticks.get_mut(&Operation::Read) {
+        w.ttfb_hist.clear();
+        w.rtt_hist.clear();
+    }
+    if let Some(w) = op_ticks.get_mut(&Operation::Write) {
+        w.ttfb_hist.clear();
+        w.rtt_hist.clear();
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -350,6 +501,41 @@ impl From<std::io::Error> for ChumError {
     }
 }
 
+/*
+ * Parse an interval string into a Duration. This accepts whole or
+ * fractional seconds (e.g. '1', '0.1') as well as an explicit millisecond
+ * suffix (e.g. '250ms'), so that `-i` can express sub-second tick cadences
+ * instead of being stuck at a 1s minimum.
+ */
+pub fn parse_interval(val: &str) -> Result<time::Duration, ChumError> {
+    if let Some(ms) = val.strip_suffix("ms") {
+        let millis: u64 = ms.parse().map_err(|_| {
+            ChumError::new(&format!("'{}' is not a valid interval", val))
+        })?;
+
+        if millis == 0 {
+            return Err(ChumError::new("interval must be greater than zero"));
+        }
+
+        return Ok(time::Duration::from_millis(millis));
+    }
+
+    let secs: f64 = val
+        .parse()
+        .map_err(|_| ChumError::new(&format!("'{}' is not a valid interval", val)))?;
+
+    if secs <= 0.0 {
+        return Err(ChumError::new("interval must be greater than zero"));
+    }
+
+    let dur = time::Duration::from_secs_f64(secs);
+    if dur.is_zero() {
+        return Err(ChumError::new("interval must be greater than zero"));
+    }
+
+    Ok(dur)
+}
+
 /* Convert a human-readable string (e.g. '4k') to bytes (e.g. '4096'). */
 pub fn parse_human(val: &str) -> Result<u64, ChumError> {
     let k = 1024;